@@ -1,7 +1,14 @@
 //! Abstract Syntax Tree
 use options;
 
+use std::collections::HashMap;
+use std::fs;
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
 /// AST of an asm function
+#[cfg_attr(feature = "json", derive(Serialize))]
 #[derive(Debug, Clone)]
 pub struct Function {
     pub id: String,
@@ -11,6 +18,7 @@ pub struct Function {
 }
 
 /// Statemets
+#[cfg_attr(feature = "json", derive(Serialize))]
 #[derive(Debug, Clone)]
 pub enum Statement {
     Label(Label),
@@ -20,6 +28,7 @@ pub enum Statement {
 }
 
 /// Asm labels, e.g., LBB0:
+#[cfg_attr(feature = "json", derive(Serialize))]
 #[derive(Debug, Clone)]
 pub struct Label {
     pub id: String,
@@ -49,6 +58,7 @@ impl Label {
 }
 
 /// Asm directives, e.g, .static ...
+#[cfg_attr(feature = "json", derive(Serialize))]
 #[derive(Debug, Clone)]
 pub enum Directive {
     File(File),
@@ -56,6 +66,7 @@ pub enum Directive {
     Generic(GenericDirective),
 }
 
+#[cfg_attr(feature = "json", derive(Serialize))]
 #[derive(PartialEq, Debug, Clone)]
 pub struct File {
     pub path: String,
@@ -82,6 +93,7 @@ impl File {
     }
 }
 
+#[cfg_attr(feature = "json", derive(Serialize))]
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub struct Loc {
     pub file_index: usize,
@@ -115,6 +127,7 @@ impl Loc {
     }
 }
 
+#[cfg_attr(feature = "json", derive(Serialize))]
 #[derive(Clone, Debug)]
 pub struct GenericDirective {
     string: String,
@@ -123,9 +136,32 @@ pub struct GenericDirective {
 impl GenericDirective {
     pub fn new(s: &str) -> Option<Self> {
         if s.starts_with(".") {
-            return Some(Self {
-                string: s.to_string(),
-            });
+            // Data references inside directives (e.g. `.quad _ZN...E`) can be
+            // mangled symbols too. Walk the line token by token, demangling
+            // only the tokens that look mangled and copying the separators
+            // verbatim, so significant whitespace in string directives like
+            // `.ascii` / `.asciz` is preserved and each token is spliced at its
+            // own position (a plain `replace` would corrupt a token that is a
+            // substring of another, or process a repeated token twice).
+            let mut string = String::with_capacity(s.len());
+            let mut rest = s;
+            while !rest.is_empty() {
+                let ws = rest.find(|c: char| !c.is_whitespace()).unwrap_or(rest.len());
+                string.push_str(&rest[..ws]);
+                rest = &rest[ws..];
+                if rest.is_empty() {
+                    break;
+                }
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                let tok = &rest[..end];
+                if looks_mangled(split_reloc(tok).0) {
+                    string.push_str(&demangle_operand(tok));
+                } else {
+                    string.push_str(tok);
+                }
+                rest = &rest[end..];
+            }
+            return Some(Self { string });
         }
         None
     }
@@ -183,6 +219,7 @@ impl Directive {
 }
 
 /// Asm comments, e.g, ;; this is a comment.
+#[cfg_attr(feature = "json", derive(Serialize))]
 #[derive(Debug, Clone)]
 pub struct Comment {
     string: String,
@@ -208,7 +245,77 @@ impl Comment {
     }
 }
 
+/// Split a relocation suffix (`@PLT`, `@GOTPCREL`, ...) off an operand,
+/// returning `(symbol, suffix)` where `suffix` keeps the leading `@`.
+fn split_reloc(arg: &str) -> (&str, &str) {
+    match arg.find('@') {
+        Some(i) => (&arg[..i], &arg[i..]),
+        None => (arg, ""),
+    }
+}
+
+/// Whether `s` looks like a mangled Rust symbol under either the legacy
+/// Itanium-style scheme (`_ZN...E`) or the `v0` scheme (`_R...`).
+fn looks_mangled(s: &str) -> bool {
+    s.starts_with("_ZN") || s.starts_with("__ZN") || looks_v0(s)
+}
+
+/// Whether `s` begins with a plausible `v0` mangled name. Beyond the `_R`
+/// prefix the grammar requires an optional decimal count followed by a `<path>`
+/// production, which always starts with one of `C M X Y N I B`; checking that
+/// keeps ordinary tokens like `_Reset` or `_RDATA` out of the demangler rather
+/// than relying on its parse-failure fallback.
+fn looks_v0(s: &str) -> bool {
+    if !s.starts_with("_R") {
+        return false;
+    }
+    let rest = s[2..].trim_start_matches(|c: char| c.is_ascii_digit());
+    match rest.chars().next() {
+        Some('C') | Some('M') | Some('X') | Some('Y') | Some('N') | Some('I') | Some('B') => true,
+        _ => false,
+    }
+}
+
+/// Demangle a single operand if it looks like a mangled Rust symbol, keeping
+/// any relocation suffix (`@PLT`, `@GOTPCREL`, ...) attached. `::demangle`
+/// handles both the legacy `_ZN...E` and the `v0` `_R...` schemes and returns
+/// the symbol unchanged when it parses as neither.
+fn demangle_operand(arg: &str) -> String {
+    let (symbol, suffix) = split_reloc(arg);
+    if !looks_mangled(symbol) {
+        return arg.to_string();
+    }
+    format!("{}{}", ::demangle::demangle(symbol), suffix)
+}
+
+/// Opcodes that transfer control to a named symbol: direct calls on x86
+/// (`call`/`callq`) and ARM (`bl`), plus `jmp` tail calls.
+fn is_call_opcode(instr: &str) -> bool {
+    match instr {
+        "call" | "callq" | "bl" | "jmp" => true,
+        _ => false,
+    }
+}
+
+/// Whether a branch/call operand names a function symbol rather than a local
+/// control-flow target. Local labels (`.LBB0_3`, `LBB0_3`) and
+/// register-indirect operands (`*%rax`, `%r11`) are not call edges: they keep
+/// a `jmp` tail call apart from an ordinary local jump.
+fn is_symbol_target(arg: &str) -> bool {
+    !(arg.starts_with('.') || arg.starts_with('L') || arg.starts_with('*')
+        || arg.starts_with('%'))
+}
+
+/// A single outgoing call found in a function body.
+#[cfg_attr(feature = "json", derive(Serialize))]
+#[derive(Debug, Clone)]
+pub struct CallSite {
+    pub target: String,
+    pub loc: Option<Loc>,
+}
+
 /// Asm instructions: everything else (not a Comment, Directive, or Label).
+#[cfg_attr(feature = "json", derive(Serialize))]
 #[derive(Debug, Clone)]
 pub struct Instruction {
     instr: String,
@@ -224,9 +331,11 @@ impl Instruction {
         for arg in iter {
             args.push(arg.to_string());
         }
-        if &instr == "call" {
-            let demangled_function = ::demangle::demangle(&args[0]);
-            args[0] = demangled_function;
+        // Demangle every operand that looks like a Rust symbol, not just the
+        // target of a `call`: `lea`/`mov` of a global address, GOT/PLT
+        // relocation operands, and other data references are symbols too.
+        for arg in &mut args {
+            *arg = demangle_operand(arg);
         }
         return Some(Self {
             instr,
@@ -237,6 +346,19 @@ impl Instruction {
     pub fn rust_loc(&self) -> Option<Loc> {
         self.rust_loc_off
     }
+    /// Whether this instruction transfers control to a symbol we can name:
+    /// direct calls (`call`/`callq`, ARM `bl`) and `jmp` tail calls.
+    pub fn is_call(&self) -> bool {
+        is_call_opcode(&self.instr) && self.args.get(0).map_or(false, |a| is_symbol_target(a))
+    }
+    /// The (already demangled) call target, if this is a call instruction.
+    pub fn call_target(&self) -> Option<&str> {
+        if self.is_call() {
+            Some(self.args[0].as_str())
+        } else {
+            None
+        }
+    }
     pub fn should_print(&self, _opts: &options::Options) -> bool {
         true
     }
@@ -289,4 +411,256 @@ impl Statement {
         }
         Some(loc.file_line)
     }
+    pub fn loc(&self) -> Option<Loc> {
+        match self {
+            // A `.loc` directive carries the location forward; consult
+            // `Directive::loc()` (not `rust_loc()`, which is `None` for every
+            // directive) so the current loc updates on the `.loc` itself.
+            &Statement::Directive(ref d) => d.loc(),
+            &Statement::Label(ref l) => l.rust_loc(),
+            &Statement::Instruction(ref l) => l.rust_loc(),
+            &Statement::Comment(ref l) => l.rust_loc(),
+        }
+    }
+}
+
+impl Function {
+    /// Table of every `.file` directive in the function keyed by its index, so
+    /// that locations referring to inlined code from another file resolve to
+    /// the right source path.
+    pub fn file_table(&self) -> HashMap<usize, File> {
+        let mut files = HashMap::new();
+        for stmt in &self.statements {
+            if let &Statement::Directive(Directive::File(ref f)) = stmt {
+                files.insert(f.index, f.clone());
+            }
+        }
+        if let Some(ref f) = self.file {
+            files.entry(f.index).or_insert_with(|| f.clone());
+        }
+        files
+    }
+
+    /// Serialize the full parsed AST of this function — its id, `file` and
+    /// `loc`, every statement (`.file`/`.loc` directives, opcodes and argument
+    /// vectors, labels and comments) — as JSON for editor/LSP integration.
+    /// Each instruction and label carries its raw `Loc` (`file_index`,
+    /// `file_line`, `file_column`) under `rust_loc_off`; no source path is
+    /// attached to a statement, so a client maps `file_index` to a path
+    /// through the `.file` directives and the function's `file`.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> ::serde_json::Result<String> {
+        ::serde_json::to_string(self)
+    }
+
+    /// The ordered list of unique callees of this function, in first-seen
+    /// order, with the call-site source line carried from each instruction's
+    /// resolved `rust_loc`.
+    pub fn call_sites(&self) -> Vec<CallSite> {
+        let mut sites = Vec::new();
+        let mut seen = HashMap::new();
+        for stmt in &self.statements {
+            if let &Statement::Instruction(ref i) = stmt {
+                if let Some(target) = i.call_target() {
+                    if seen.insert(target.to_string(), ()).is_none() {
+                        sites.push(CallSite {
+                            target: target.to_string(),
+                            loc: i.rust_loc(),
+                        });
+                    }
+                }
+            }
+        }
+        sites
+    }
+
+    /// Print the function, selecting the source-interleaved view when the
+    /// `rust` option is set and the plain statement listing otherwise. This is
+    /// the entry point the output path uses for a `Function`.
+    pub fn format(&self, opts: &options::Options) -> String {
+        if opts.rust {
+            return self.format_interleaved(opts);
+        }
+        let mut out = String::new();
+        for stmt in &self.statements {
+            if stmt.should_print(opts) {
+                out.push_str(&stmt.format(opts));
+                out.push('\n');
+            }
+        }
+        out
+    }
+
+    /// Render the function with its Rust source interleaved: consecutive
+    /// statements sharing a resolved `(file_index, file_line)` are printed
+    /// under a header showing the originating source line. A `.loc` precedes
+    /// the instructions it annotates, so the current location is carried
+    /// forward to every following statement until the next `.loc`.
+    pub fn format_interleaved(&self, opts: &options::Options) -> String {
+        let files = self.file_table();
+        let mut source_cache: HashMap<usize, Option<Vec<String>>> = HashMap::new();
+        let mut out = String::new();
+        let mut cur_loc: Option<Loc> = self.loc;
+        // Group by `(file_index, file_line)` only: rustc emits many `.loc` for
+        // one source line with differing columns, and comparing whole `Loc`s
+        // (which include `file_column`) would reprint the header on every one.
+        let mut last_header: Option<(usize, usize)> = None;
+        for stmt in &self.statements {
+            if let Some(loc) = stmt.loc() {
+                cur_loc = Some(loc);
+            }
+            if !stmt.should_print(opts) {
+                continue;
+            }
+            if let Some(loc) = cur_loc {
+                let key = (loc.file_index, loc.file_line);
+                if last_header != Some(key) {
+                    if let Some(line) = source_line(&files, &mut source_cache, loc) {
+                        out.push_str(&format!("{}:{}: {}\n", file_name(&files, loc), loc.file_line, line));
+                    }
+                    last_header = Some(key);
+                }
+            }
+            out.push_str(&stmt.format(opts));
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Name of the file a location points at, or a bare index when unknown.
+fn file_name(files: &HashMap<usize, File>, loc: Loc) -> String {
+    files
+        .get(&loc.file_index)
+        .map(|f| f.path.clone())
+        .unwrap_or_else(|| format!("<file {}>", loc.file_index))
+}
+
+/// Resolve a location to its source line, reading (and caching) the file. Any
+/// unreadable file or out-of-range line resolves to `None` so callers can fall
+/// back to printing the raw assembly only.
+fn source_line(
+    files: &HashMap<usize, File>,
+    cache: &mut HashMap<usize, Option<Vec<String>>>,
+    loc: Loc,
+) -> Option<String> {
+    if !cache.contains_key(&loc.file_index) {
+        let lines = files
+            .get(&loc.file_index)
+            .and_then(|f| fs::read_to_string(&f.path).ok())
+            .map(|s| s.lines().map(|l| l.to_string()).collect());
+        cache.insert(loc.file_index, lines);
+    }
+    let lines = cache.get(&loc.file_index)?.as_ref()?;
+    if loc.file_line == 0 || loc.file_line > lines.len() {
+        return None;
+    }
+    Some(lines[loc.file_line - 1].trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use options::Options;
+
+    fn instr(op: &str, arg: &str, loc: Option<Loc>) -> Statement {
+        Statement::Instruction(Instruction {
+            instr: op.to_string(),
+            args: vec![arg.to_string()],
+            rust_loc_off: loc,
+        })
+    }
+
+    #[test]
+    fn split_reloc_round_trips() {
+        assert_eq!(split_reloc("foo@GOTPCREL"), ("foo", "@GOTPCREL"));
+        assert_eq!(split_reloc("foo@PLT"), ("foo", "@PLT"));
+        assert_eq!(split_reloc("foo"), ("foo", ""));
+        // A bare non-mangled operand keeps its suffix untouched.
+        assert_eq!(demangle_operand("foo@PLT"), "foo@PLT");
+    }
+
+    #[test]
+    fn looks_mangled_accepts_schemes_and_rejects_lookalikes() {
+        assert!(looks_mangled("_ZN4core3fmtE"));
+        assert!(looks_mangled("_RNvCs1234_4core"));
+        assert!(looks_mangled("_RC4core"));
+        // `_R`-prefixed tokens that are not v0 names must not be matched.
+        assert!(!looks_mangled("_Reset"));
+        assert!(!looks_mangled("_RDATA"));
+        assert!(!looks_mangled("memcpy"));
+    }
+
+    #[test]
+    fn symbol_targets_exclude_labels_and_registers() {
+        assert!(is_symbol_target("memcpy"));
+        assert!(is_symbol_target("core::ptr::drop_in_place"));
+        assert!(!is_symbol_target(".LBB0_3"));
+        assert!(!is_symbol_target("LBB0_3"));
+        assert!(!is_symbol_target("*%rax"));
+        assert!(!is_symbol_target("%r11"));
+    }
+
+    #[test]
+    fn jmp_to_local_label_is_not_a_call_edge() {
+        let f = Function {
+            id: "f".to_string(),
+            file: None,
+            loc: None,
+            statements: vec![
+                instr("jmp", ".LBB0_3", None),
+                instr("jmp", "some_tail_callee", None),
+            ],
+        };
+        let targets: Vec<_> = f.call_sites().into_iter().map(|c| c.target).collect();
+        assert_eq!(targets, vec!["some_tail_callee".to_string()]);
+    }
+
+    #[test]
+    fn call_sites_dedup_and_preserve_order() {
+        let f = Function {
+            id: "f".to_string(),
+            file: None,
+            loc: None,
+            statements: vec![
+                instr("call", "bar", None),
+                instr("jmp", ".LBB0_1", None),
+                instr("callq", "foo", None),
+                instr("call", "bar", None),
+            ],
+        };
+        let targets: Vec<_> = f.call_sites().into_iter().map(|c| c.target).collect();
+        assert_eq!(targets, vec!["bar".to_string(), "foo".to_string()]);
+    }
+
+    #[test]
+    fn interleave_groups_consecutive_statements_by_line() {
+        let path = ::std::env::temp_dir().join("cargo_asm_interleave_test.rs");
+        fs::write(&path, "fn a() {}\nlet x = 1 + 2;\n").unwrap();
+        let file = File {
+            path: path.to_str().unwrap().to_string(),
+            index: 1,
+        };
+        let at = |line, col| {
+            Some(Loc {
+                file_index: 1,
+                file_line: line,
+                file_column: col,
+            })
+        };
+        let f = Function {
+            id: "f".to_string(),
+            file: Some(file.clone()),
+            loc: None,
+            statements: vec![
+                Statement::Directive(Directive::File(file)),
+                // Two instructions from line 2 with differing columns: the
+                // header must print once, not once per column.
+                instr("mov", "eax", at(2, 5)),
+                instr("add", "eax", at(2, 9)),
+            ],
+        };
+        let out = f.format_interleaved(&Options::default());
+        assert_eq!(out.matches("let x = 1 + 2;").count(), 1);
+    }
 }